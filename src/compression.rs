@@ -0,0 +1,144 @@
+//! `Accept-Encoding` negotiation and response body compression.
+
+use tiny_http::{Header, Request};
+
+/// Picks a supported encoding from an `Accept-Encoding` header value, if any.
+fn requested_encoding(accept_encoding: Option<&str>) -> Option<&'static str> {
+    let offered: Vec<&str> = accept_encoding
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|e| quality(e) > 0.0)
+        .collect();
+
+    #[cfg(feature = "brotli")]
+    if offered.iter().any(|e| coding_name(e).eq_ignore_ascii_case("br")) {
+        return Some("br");
+    }
+    #[cfg(feature = "gzip")]
+    if offered.iter().any(|e| coding_name(e).eq_ignore_ascii_case("gzip")) {
+        return Some("gzip");
+    }
+
+    let _ = offered;
+    None
+}
+
+/// The coding name of an `Accept-Encoding` token, with any `;q=...` parameter stripped.
+fn coding_name(token: &str) -> &str {
+    token.split(';').next().unwrap_or(token).trim()
+}
+
+/// The quality value of an `Accept-Encoding` token (e.g. `0` for `gzip;q=0`),
+/// defaulting to `1.0` when no `;q=` parameter is present or it fails to parse.
+fn quality(token: &str) -> f32 {
+    token
+        .split(';')
+        .skip(1)
+        .find_map(|param| param.trim().strip_prefix("q="))
+        .and_then(|q| q.trim().parse().ok())
+        .unwrap_or(1.0)
+}
+
+/// Compresses `body` according to the request's `Accept-Encoding`, returning the
+/// (possibly unmodified) body and the `Content-Encoding` header to attach, if any.
+pub(crate) fn encode(req: &Request, body: Vec<u8>) -> (Vec<u8>, Option<Header>) {
+    let accept_encoding = req
+        .headers()
+        .iter()
+        .find(|h| h.field.equiv("Accept-Encoding"))
+        .map(|h| h.value.as_str());
+
+    match requested_encoding(accept_encoding) {
+        #[cfg(feature = "brotli")]
+        Some("br") => {
+            let header = Header::from_bytes(&b"Content-Encoding"[..], &b"br"[..]).unwrap();
+            (brotli(&body), Some(header))
+        }
+        #[cfg(feature = "gzip")]
+        Some("gzip") => {
+            let header = Header::from_bytes(&b"Content-Encoding"[..], &b"gzip"[..]).unwrap();
+            (gzip(&body), Some(header))
+        }
+        _ => (body, None),
+    }
+}
+
+#[cfg(feature = "gzip")]
+fn gzip(data: &[u8]) -> Vec<u8> {
+    use std::io::Write;
+
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder
+        .write_all(data)
+        .expect("writing to an in-memory encoder cannot fail");
+    encoder.finish().expect("finishing an in-memory encoder cannot fail")
+}
+
+#[cfg(feature = "brotli")]
+fn brotli(data: &[u8]) -> Vec<u8> {
+    let mut compressed = Vec::new();
+    let params = brotli::enc::BrotliEncoderParams::default();
+    brotli::BrotliCompress(&mut &data[..], &mut compressed, &params)
+        .expect("compressing an in-memory buffer cannot fail");
+    compressed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quality_defaults_to_one() {
+        assert_eq!(quality("gzip"), 1.0);
+    }
+
+    #[test]
+    fn quality_parses_q_parameter() {
+        assert_eq!(quality("gzip;q=0.5"), 0.5);
+        assert_eq!(quality("gzip; q=0.5"), 0.5);
+    }
+
+    #[test]
+    fn quality_malformed_q_defaults_to_one() {
+        assert_eq!(quality("gzip;q=not-a-number"), 1.0);
+    }
+
+    #[test]
+    fn coding_name_strips_q_parameter() {
+        assert_eq!(coding_name("gzip;q=0.5"), "gzip");
+        assert_eq!(coding_name("br"), "br");
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn requested_encoding_excludes_q_zero() {
+        assert_eq!(requested_encoding(Some("gzip;q=0")), None);
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn requested_encoding_is_case_insensitive() {
+        assert_eq!(requested_encoding(Some("GZIP")), Some("gzip"));
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn requested_encoding_picks_gzip_when_offered() {
+        assert_eq!(requested_encoding(Some("identity, gzip")), Some("gzip"));
+    }
+
+    #[cfg(all(feature = "gzip", feature = "brotli"))]
+    #[test]
+    fn requested_encoding_skips_excluded_encoding_in_favor_of_another() {
+        assert_eq!(requested_encoding(Some("gzip;q=0, br;q=0.8")), Some("br"));
+    }
+
+    #[test]
+    fn requested_encoding_with_no_header_is_none() {
+        assert_eq!(requested_encoding(None), None);
+    }
+}
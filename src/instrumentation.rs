@@ -0,0 +1,62 @@
+//! Opt-in self-metrics for the endpoint's own scrape traffic.
+//!
+//! This is what backs [`MetricsServer::with_self_instrumentation`](crate::MetricsServer::with_self_instrumentation);
+//! it has no reason to be used on its own.
+
+use std::time::Duration;
+
+use prometheus_client::encoding::EncodeLabelSet;
+use prometheus_client::metrics::counter::Counter;
+use prometheus_client::metrics::family::Family;
+use prometheus_client::metrics::histogram::{exponential_buckets, Histogram};
+use prometheus_client::registry::Registry;
+use tiny_http::Method;
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+struct RequestLabels {
+    method: String,
+    status: u16,
+}
+
+/// Tracks `metrics_server_http_requests` and
+/// `metrics_server_http_request_duration_seconds` for the server it's attached to.
+#[derive(Clone)]
+pub(crate) struct Instrumentation {
+    requests_total: Family<RequestLabels, Counter>,
+    request_duration_seconds: Histogram,
+}
+
+impl Instrumentation {
+    /// Registers the self-metrics into `registry`.
+    pub(crate) fn register(registry: &mut Registry) -> Self {
+        let requests_total = Family::<RequestLabels, Counter>::default();
+        let request_duration_seconds = Histogram::new(exponential_buckets(0.001, 2.0, 10));
+
+        registry.register(
+            "metrics_server_http_requests",
+            "Total HTTP requests handled by this metrics endpoint, by method and status",
+            requests_total.clone(),
+        );
+        registry.register(
+            "metrics_server_http_request_duration_seconds",
+            "Latency of HTTP requests handled by this metrics endpoint",
+            request_duration_seconds.clone(),
+        );
+
+        Instrumentation {
+            requests_total,
+            request_duration_seconds,
+        }
+    }
+
+    /// Records one handled request.
+    pub(crate) fn observe(&self, method: &Method, status: u16, elapsed: Duration) {
+        self.requests_total
+            .get_or_create(&RequestLabels {
+                method: method.to_string(),
+                status,
+            })
+            .inc();
+        self.request_duration_seconds.observe(elapsed.as_secs_f64());
+    }
+}
@@ -0,0 +1,11 @@
+//! A tiny HTTP server for exposing metrics, built on top of [`tiny_http`].
+
+#[cfg(any(feature = "gzip", feature = "brotli"))]
+mod compression;
+#[cfg(feature = "prometheus-client")]
+mod instrumentation;
+#[cfg(all(feature = "prometheus-client", feature = "process-metrics"))]
+mod process;
+mod server;
+
+pub use server::{MetricsServer, MetricsServerHandle};
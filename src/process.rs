@@ -0,0 +1,87 @@
+//! Built-in process/system self-metrics, sampled on a background thread.
+//!
+//! This is what backs [`MetricsServer::with_process_metrics`](crate::MetricsServer::with_process_metrics);
+//! it has no reason to be used on its own.
+
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use prometheus_client::metrics::counter::Counter;
+use prometheus_client::metrics::gauge::Gauge;
+use prometheus_client::registry::Registry;
+use sysinfo::{Pid, ProcessRefreshKind, System};
+
+/// Fixed process facts captured once at startup, since they don't change for
+/// the lifetime of the process.
+struct Startup {
+    pid: Pid,
+    start_time_seconds: f64,
+}
+
+impl Startup {
+    fn now() -> Self {
+        let start_time_seconds = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs_f64();
+        Startup {
+            pid: Pid::from_u32(std::process::id()),
+            start_time_seconds,
+        }
+    }
+}
+
+/// Registers `process_*` gauges and a counter into `registry` and spawns a background thread
+/// that samples them from `sysinfo` roughly once per `interval`, until `shutdown`
+/// is set, mirroring how [`MetricsServerHandle::shutdown`](crate::MetricsServerHandle::shutdown)
+/// tears down the request-handling thread.
+pub(crate) fn spawn(registry: &mut Registry, interval: Duration, shutdown: Arc<AtomicBool>) {
+    let startup = Startup::now();
+
+    let resident_memory = Gauge::<f64, AtomicU64>::default();
+    let cpu_seconds_total = Counter::<f64, AtomicU64>::default();
+    let start_time = Gauge::<f64, AtomicU64>::default();
+    start_time.set(startup.start_time_seconds);
+
+    registry.register(
+        "process_resident_memory_bytes",
+        "Resident memory size in bytes",
+        resident_memory.clone(),
+    );
+    registry.register(
+        "process_cpu_seconds",
+        "Total user and system CPU time spent in seconds",
+        cpu_seconds_total.clone(),
+    );
+    registry.register(
+        "process_start_time_seconds",
+        "Start time of the process since unix epoch in seconds",
+        start_time,
+    );
+
+    thread::spawn(move || {
+        let pid = startup.pid;
+        let mut system = System::new();
+        while !shutdown.load(Ordering::SeqCst) {
+            system.refresh_process_specifics(pid, ProcessRefreshKind::everything());
+            if let Some(process) = system.process(pid) {
+                resident_memory.set(process.memory() as f64);
+                cpu_seconds_total.inc_by(process.cpu_usage() as f64 / 100.0 * interval.as_secs_f64());
+            }
+            sleep_interruptibly(interval, &shutdown);
+        }
+    });
+}
+
+/// Sleeps for `interval`, but wakes up in short increments to check `shutdown` so
+/// it doesn't have to wait out a potentially long sampling interval to exit.
+fn sleep_interruptibly(interval: Duration, shutdown: &AtomicBool) {
+    let step = Duration::from_millis(100).min(interval);
+    let mut slept = Duration::ZERO;
+    while slept < interval && !shutdown.load(Ordering::SeqCst) {
+        thread::sleep(step);
+        slept += step;
+    }
+}
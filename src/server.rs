@@ -1,11 +1,84 @@
-use std::sync::{Arc, Mutex};
-use std::thread;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+#[cfg(all(feature = "prometheus-client", feature = "process-metrics"))]
+use std::sync::atomic::AtomicUsize;
+use std::sync::Arc;
+#[cfg(feature = "prometheus-client")]
+use std::sync::Mutex;
+use std::thread::{self, JoinHandle};
+#[cfg(feature = "prometheus-client")]
+use std::time::Instant;
+#[cfg(all(feature = "prometheus-client", feature = "process-metrics"))]
+use std::time::Duration;
 
-use tiny_http::{Method, Response, Server};
+use arc_swap::ArcSwap;
+#[cfg(feature = "prometheus-client")]
+use prometheus_client::registry::Registry;
+use tiny_http::{Header, Method, Response, Server};
+#[cfg(feature = "tls")]
+use tiny_http::SslConfig;
 
-/// A thread-safe growable array.
+#[cfg(feature = "prometheus-client")]
+use crate::instrumentation::Instrumentation;
+
+/// Where a `MetricsServer` reads the bytes it serves from.
 #[derive(Clone)]
-pub struct MetricsServer(Arc<Mutex<Vec<u8>>>);
+enum Source {
+    /// A plain, user-populated buffer updated via [`MetricsServer::update`].
+    Buffer(Arc<ArcSwap<Vec<u8>>>),
+    /// A `prometheus-client` registry, encoded to OpenMetrics text on every scrape.
+    #[cfg(feature = "prometheus-client")]
+    Registry(Arc<Mutex<Registry>>),
+}
+
+/// A shared closure that produces a response body on demand, the same shape as
+/// the plain closures [`MetricsServer::route`] accepts.
+pub type RouteHandler = Arc<dyn Fn() -> Vec<u8> + Send + Sync>;
+
+/// An entry in the route table: produces a response body and, optionally, a
+/// `Content-Type` header to attach to it.
+///
+/// `/metrics` is pre-populated with one of these by [`MetricsServer::from_source`]
+/// so that it's just the default entry in `routes`, rather than a special case
+/// the dispatch loop has to know about; [`route`](MetricsServer::route) wraps
+/// the plain `Fn() -> Vec<u8>` closures it is given into one of these with no
+/// header.
+#[derive(Clone)]
+struct RouteEntry {
+    handler: Arc<dyn Fn() -> (Vec<u8>, Option<Header>) + Send + Sync>,
+}
+
+/// Ties a [`with_process_metrics`](MetricsServer::with_process_metrics) sampling
+/// thread's lifetime to every [`MetricsServerHandle`] derived from that server,
+/// rather than to any single one of them — e.g. a `serve()` and a `serve_tls()`
+/// handle built from the same server each hold a reference, and the thread only
+/// stops once both have been shut down.
+#[cfg(all(feature = "prometheus-client", feature = "process-metrics"))]
+#[derive(Clone)]
+struct ProcessMetricsShutdown {
+    flag: Arc<AtomicBool>,
+    live_handles: Arc<AtomicUsize>,
+}
+
+/// A thread-safe metrics endpoint, backed by either a plain buffer or a
+/// `prometheus-client` registry.
+#[derive(Clone)]
+pub struct MetricsServer {
+    /// Kept alongside `routes` only so [`update`](Self::update) has something
+    /// to write into; `/metrics` itself is served via its entry in `routes`.
+    source: Source,
+    /// Registered paths, e.g. `/metrics` (the default, registered by
+    /// [`from_source`](Self::from_source)) and anything added via
+    /// [`route`](Self::route), e.g. `/healthz`.
+    routes: HashMap<String, RouteEntry>,
+    #[cfg(feature = "prometheus-client")]
+    instrumentation: Option<Instrumentation>,
+    /// Set when [`with_process_metrics`](Self::with_process_metrics) spawned a
+    /// sampling thread, so every [`MetricsServerHandle`] derived from this server
+    /// can share responsibility for stopping it.
+    #[cfg(all(feature = "prometheus-client", feature = "process-metrics"))]
+    process_metrics_shutdown: Option<ProcessMetricsShutdown>,
+}
 
 impl Default for MetricsServer {
     fn default() -> Self {
@@ -13,10 +86,48 @@ impl Default for MetricsServer {
     }
 }
 
+/// A handle to a `MetricsServer` that is currently [`serve`](MetricsServer::serve)ing
+/// requests on a background thread.
+///
+/// Dropping this handle leaves the server running; call [`shutdown`](Self::shutdown)
+/// to stop it and wait for its thread to exit.
+pub struct MetricsServerHandle {
+    server: Arc<Server>,
+    shutdown: Arc<AtomicBool>,
+    handle: JoinHandle<()>,
+    #[cfg(all(feature = "prometheus-client", feature = "process-metrics"))]
+    process_metrics_shutdown: Option<ProcessMetricsShutdown>,
+}
+
+impl MetricsServerHandle {
+    /// Stops the server and blocks until its request-handling thread has exited.
+    ///
+    /// This unblocks the accept loop via [`Server::unblock`], so any request the
+    /// server is currently waiting on is abandoned rather than serviced. If the
+    /// `MetricsServer` was created with
+    /// [`with_process_metrics`](crate::MetricsServer::with_process_metrics), this
+    /// also releases this handle's claim on its sampling thread, stopping it once
+    /// every other handle derived from that same server has done the same (e.g. if
+    /// both a `serve()` and a `serve_tls()` handle were built from it).
+    pub fn shutdown(self) {
+        self.shutdown.store(true, Ordering::SeqCst);
+        self.server.unblock();
+        let _ = self.handle.join();
+
+        #[cfg(all(feature = "prometheus-client", feature = "process-metrics"))]
+        if let Some(process_metrics_shutdown) = &self.process_metrics_shutdown {
+            if process_metrics_shutdown.live_handles.fetch_sub(1, Ordering::SeqCst) == 1 {
+                process_metrics_shutdown.flag.store(true, Ordering::SeqCst);
+            }
+        }
+    }
+}
+
 impl MetricsServer {
     /// Creates a new empty `MetricsServer`.
     ///
-    /// This will create a mutex protected empty Vector. It will not allocate.
+    /// This will create an empty, atomically swappable Vector and the default
+    /// `/metrics` route. The empty Vector itself does not allocate.
     ///
     /// # Examples
     ///
@@ -26,14 +137,138 @@ impl MetricsServer {
     /// let server = MetricsServer::new();
     /// ```
     pub fn new() -> Self {
-        MetricsServer(Arc::new(Mutex::new(Vec::new())))
+        Self::from_source(Source::Buffer(Arc::new(ArcSwap::from_pointee(Vec::new()))))
+    }
+
+    fn from_source(source: Source) -> Self {
+        let mut routes = HashMap::new();
+        routes.insert("/metrics".to_string(), Self::metrics_route_entry(&source));
+
+        MetricsServer {
+            source,
+            routes,
+            #[cfg(feature = "prometheus-client")]
+            instrumentation: None,
+            #[cfg(all(feature = "prometheus-client", feature = "process-metrics"))]
+            process_metrics_shutdown: None,
+        }
+    }
+
+    /// Builds the default `/metrics` route entry for `source`: a plain buffer is
+    /// served as-is, while a registry is encoded to OpenMetrics text on every
+    /// request, with the matching `Content-Type` header.
+    fn metrics_route_entry(source: &Source) -> RouteEntry {
+        let source = source.clone();
+        RouteEntry {
+            handler: Arc::new(move || match &source {
+                // Load a cheap, reference-counted snapshot of the metrics without
+                // ever blocking on a concurrent `update()`.
+                Source::Buffer(buf) => (buf.load().to_vec(), None),
+                #[cfg(feature = "prometheus-client")]
+                Source::Registry(registry) => {
+                    let mut body = String::new();
+                    prometheus_client::encoding::text::encode(&mut body, &registry.lock().unwrap()).unwrap();
+                    let content_type = Header::from_bytes(
+                        &b"Content-Type"[..],
+                        &b"application/openmetrics-text; version=1.0.0; charset=utf-8"[..],
+                    )
+                    .unwrap();
+                    (body.into_bytes(), Some(content_type))
+                }
+            }),
+        }
+    }
+
+    /// Registers an additional path, served by calling `handler` on every
+    /// matching request.
+    ///
+    /// This is useful for things like a `/healthz` liveness probe that always
+    /// returns 200, or for exposing a second, differently-scoped set of metrics.
+    ///
+    /// `/metrics` is itself just the default entry in this route table, kept for
+    /// backward compatibility; calling `.route("/metrics", ...)` overrides it.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use metrics_server::MetricsServer;
+    ///
+    /// let server = MetricsServer::new().route("/healthz", || Vec::new());
+    /// ```
+    pub fn route(mut self, path: impl Into<String>, handler: impl Fn() -> Vec<u8> + Send + Sync + 'static) -> Self {
+        self.routes.insert(
+            path.into(),
+            RouteEntry {
+                handler: Arc::new(move || (handler(), None)),
+            },
+        );
+        self
+    }
+
+    /// Creates a `MetricsServer` backed by a `prometheus-client` [`Registry`].
+    ///
+    /// Rather than pre-serializing metrics and calling [`update`](Self::update),
+    /// users register counters/gauges on the registry once and the request handler
+    /// encodes it to OpenMetrics text on every scrape.
+    ///
+    /// Requires the `prometheus-client` feature.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::sync::{Arc, Mutex};
+    ///
+    /// use metrics_server::MetricsServer;
+    /// use prometheus_client::registry::Registry;
+    ///
+    /// let registry = Arc::new(Mutex::new(Registry::default()));
+    /// let server = MetricsServer::from_registry(registry);
+    /// ```
+    #[cfg(feature = "prometheus-client")]
+    pub fn from_registry(registry: Arc<Mutex<Registry>>) -> Self {
+        Self::from_source(Source::Registry(registry))
+    }
+
+    /// Creates a `MetricsServer` that merges the `process_resident_memory_bytes`
+    /// and `process_start_time_seconds` gauges and the `process_cpu_seconds`
+    /// counter into `registry`, sampled on a background thread roughly once every `interval`.
+    ///
+    /// Use this alongside [`from_registry`](Self::from_registry)'s `registry` to
+    /// get host self-metrics for free next to whatever counters/gauges the
+    /// caller has already registered.
+    ///
+    /// Requires the `prometheus-client` and `process-metrics` features.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::sync::{Arc, Mutex};
+    /// use std::time::Duration;
+    ///
+    /// use metrics_server::MetricsServer;
+    /// use prometheus_client::registry::Registry;
+    ///
+    /// let registry = Arc::new(Mutex::new(Registry::default()));
+    /// let server = MetricsServer::with_process_metrics(registry, Duration::from_secs(15));
+    /// ```
+    #[cfg(all(feature = "prometheus-client", feature = "process-metrics"))]
+    pub fn with_process_metrics(registry: Arc<Mutex<Registry>>, interval: Duration) -> Self {
+        let flag = Arc::new(AtomicBool::new(false));
+        crate::process::spawn(&mut registry.lock().unwrap(), interval, Arc::clone(&flag));
+
+        let mut server = Self::from_source(Source::Registry(registry));
+        server.process_metrics_shutdown = Some(ProcessMetricsShutdown {
+            flag,
+            live_handles: Arc::new(AtomicUsize::new(0)),
+        });
+        server
     }
 
     /// Safely updates the data in a `MetricsServer` and returns the number of
     /// bytes written.
     ///
-    /// This function is thread safe and protected by a mutex. It is safe
-    /// to call concurrently from multiple threads.
+    /// This function is thread safe and lock-free: it atomically swaps in a new
+    /// buffer, so it never blocks concurrent readers or other writers.
     ///
     /// # Examples
     ///
@@ -44,76 +279,206 @@ impl MetricsServer {
     /// let bytes = server.update(Vec::from([1, 2, 3, 4]));
     /// assert_eq!(bytes, 4);
     /// ```
+    ///
+    /// # Panics
+    ///
+    /// Panics if called on a `MetricsServer` created with
+    /// [`from_registry`](Self::from_registry), which serves its registry directly
+    /// instead of a user-managed buffer.
     pub fn update(&self, data: Vec<u8>) -> usize {
-        let mut buf = self.0.lock().unwrap();
-        *buf = data;
-        buf.as_slice().len()
+        #[cfg(feature = "prometheus-client")]
+        let buf = match &self.source {
+            Source::Buffer(buf) => buf,
+            Source::Registry(_) => panic!("update() is not supported on a registry-backed MetricsServer"),
+        };
+        // Without the `prometheus-client` feature, `Source::Buffer` is the only variant,
+        // so this destructure is irrefutable.
+        #[cfg(not(feature = "prometheus-client"))]
+        let Source::Buffer(buf) = &self.source;
+
+        let len = data.len();
+        buf.store(Arc::new(data));
+        len
+    }
+
+    /// Records `metrics_server_http_requests` (by method and status) and
+    /// `metrics_server_http_request_duration_seconds` into `registry` for every
+    /// request this server handles, alongside whatever it already serves.
+    ///
+    /// Requires the `prometheus-client` feature.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::sync::{Arc, Mutex};
+    ///
+    /// use metrics_server::MetricsServer;
+    /// use prometheus_client::registry::Registry;
+    ///
+    /// let registry = Arc::new(Mutex::new(Registry::default()));
+    /// let server = MetricsServer::from_registry(Arc::clone(&registry))
+    ///     .with_self_instrumentation(&registry);
+    /// ```
+    #[cfg(feature = "prometheus-client")]
+    pub fn with_self_instrumentation(mut self, registry: &Arc<Mutex<Registry>>) -> Self {
+        self.instrumentation = Some(Instrumentation::register(&mut registry.lock().unwrap()));
+        self
     }
 
     /// Starts a simple HTTP server on a new thread at the given address and expose the stored metrics.
     /// This server is intended to only be queried synchronously as it blocks upon receiving
     /// each request.
     ///
+    /// Returns a [`MetricsServerHandle`] that callers can use to shut the server down
+    /// deterministically, e.g. from their own `SIGINT` handling.
+    ///
     /// # Examples
     ///
     /// ```
     /// use metrics_server::MetricsServer;
     ///
     /// let server = MetricsServer::new();
-    /// server.serve("localhost:8001");
+    /// let handle = server.serve("localhost:8001");
+    /// handle.shutdown();
     /// ```
     ///
     /// # Panics
     ///
     /// Panics if given an invalid address.
-    pub fn serve(&self, addr: &str) {
+    pub fn serve(&self, addr: &str) -> MetricsServerHandle {
         // Create a new HTTP server and bind to the given address.
         let server = Server::http(addr).unwrap();
+        self.serve_with(server)
+    }
+
+    /// Starts a TLS-terminated HTTP server on a new thread at the given address and
+    /// expose the stored metrics over `https://`.
+    ///
+    /// `cert_chain` and `private_key` are the PEM-encoded certificate chain and
+    /// private key to present to clients. The handler loop and routing are identical
+    /// to [`serve`](Self::serve); only the server construction differs.
+    ///
+    /// Requires the `tls` feature.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use metrics_server::MetricsServer;
+    ///
+    /// let server = MetricsServer::new();
+    /// let cert_chain = std::fs::read("cert.pem").unwrap();
+    /// let private_key = std::fs::read("key.pem").unwrap();
+    /// let handle = server.serve_tls("localhost:8001", cert_chain, private_key);
+    /// handle.shutdown();
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Panics if given an invalid address or an invalid certificate/key pair.
+    #[cfg(feature = "tls")]
+    pub fn serve_tls(&self, addr: &str, cert_chain: Vec<u8>, private_key: Vec<u8>) -> MetricsServerHandle {
+        // Create a new HTTPS server and bind to the given address.
+        let server = Server::https(
+            addr,
+            SslConfig {
+                certificate: cert_chain,
+                private_key,
+            },
+        )
+        .unwrap();
+        self.serve_with(server)
+    }
+
+    /// Shared accept loop for both [`serve`](Self::serve) and
+    /// [`serve_tls`](Self::serve_tls); only the `Server` construction differs
+    /// between the two.
+    fn serve_with(&self, server: Server) -> MetricsServerHandle {
+        let server = Arc::new(server);
+        let shutdown = Arc::new(AtomicBool::new(false));
 
         // Invoking clone on Arc produces a new Arc instance, which points to the
         // same allocation on the heap as the source Arc, while increasing a reference count.
-        let buf = Arc::clone(&self.0);
+        // Cloning `routes` copies the table itself, but each entry's handler is
+        // such an Arc, so the thread shares the same handlers.
+        let routes = self.routes.clone();
+        #[cfg(feature = "prometheus-client")]
+        let instrumentation = self.instrumentation.clone();
+        let server_thread = Arc::clone(&server);
+        let shutdown_thread = Arc::clone(&shutdown);
 
         // Handle requests in a new thread so we can process in the background.
-        thread::spawn({
+        let handle = thread::spawn({
             move || {
                 loop {
                     // Blocks until the next request is received.
-                    let req = match server.recv() {
+                    let req = match server_thread.recv() {
                         Ok(req) => req,
                         Err(e) => {
+                            // `Server::unblock` causes `recv` to return an error; treat
+                            // that as a shutdown request rather than logging noise.
+                            if shutdown_thread.load(Ordering::SeqCst) {
+                                break;
+                            }
                             eprintln!("error: {}", e);
                             continue;
                         }
                     };
 
-                    // Only respond to GET requests(?).
-                    if req.method() != &Method::Get {
-                        let res = Response::empty(405);
-                        if let Err(e) = req.respond(res) {
-                            eprintln!("{}", e);
-                        };
-                        continue;
-                    }
+                    #[cfg(feature = "prometheus-client")]
+                    let start = Instant::now();
+                    #[cfg(feature = "prometheus-client")]
+                    let method = req.method().clone();
+
+                    // Only respond to GET requests(?). `/metrics` is just the default
+                    // entry in `routes`, pre-registered by `from_source`, so it's
+                    // dispatched the same way as anything added via `route()`;
+                    // anything not in the table is a 404.
+                    let (status, body, content_type): (u16, Vec<u8>, Option<Header>) = if req.method() != &Method::Get
+                    {
+                        (405, Vec::new(), None)
+                    } else if let Some(entry) = routes.get(req.url()) {
+                        let (body, content_type) = (entry.handler)();
+                        (200, body, content_type)
+                    } else {
+                        (404, Vec::new(), None)
+                    };
+
+                    #[cfg(any(feature = "gzip", feature = "brotli"))]
+                    let (body, content_encoding) = crate::compression::encode(&req, body);
+                    #[cfg(not(any(feature = "gzip", feature = "brotli")))]
+                    let content_encoding: Option<Header> = None;
 
-                    // TODO: this is naive. Fix it(?)
-                    // Only serve the /metrics path.
-                    if req.url() != "/metrics" {
-                        let res = Response::empty(404);
-                        if let Err(e) = req.respond(res) {
-                            eprintln!("{}", e);
-                        };
-                        continue;
+                    let mut res = Response::from_data(body).with_status_code(status);
+                    if let Some(header) = content_type {
+                        res = res.with_header(header);
+                    }
+                    if let Some(header) = content_encoding {
+                        res = res.with_header(header);
                     }
 
-                    // Write the metrics to the response buffer.
-                    let metrics = buf.lock().unwrap();
-                    let res = Response::from_data(metrics.as_slice());
-                    if let Err(e) = req.respond(res) {
+                    let result = req.respond(res);
+                    #[cfg(feature = "prometheus-client")]
+                    if let Some(instrumentation) = &instrumentation {
+                        instrumentation.observe(&method, status, start.elapsed());
+                    }
+                    if let Err(e) = result {
                         eprintln!("{}", e);
                     };
                 }
             }
         });
+
+        #[cfg(all(feature = "prometheus-client", feature = "process-metrics"))]
+        if let Some(process_metrics_shutdown) = &self.process_metrics_shutdown {
+            process_metrics_shutdown.live_handles.fetch_add(1, Ordering::SeqCst);
+        }
+
+        MetricsServerHandle {
+            server,
+            shutdown,
+            handle,
+            #[cfg(all(feature = "prometheus-client", feature = "process-metrics"))]
+            process_metrics_shutdown: self.process_metrics_shutdown.clone(),
+        }
     }
 }